@@ -18,17 +18,32 @@
 //! signed.
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::address_lookup_table;
+use anchor_lang::solana_program::address_lookup_table::state::AddressLookupTable;
 use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke;
 use anchor_lang::solana_program::program::invoke_signed;
 use anchor_lang::solana_program::system_instruction;
 use anchor_lang::AccountsClose;
-use std::collections::hash_map::DefaultHasher;
+use spl_token::instruction as token_instruction;
 use std::convert::Into;
-use std::hash::{Hash, Hasher};
-use std::ops::{Deref, DerefMut};
+use std::ops::DerefMut;
 
 declare_id!("FeqQXwTJvmt6YbLTzibZJVvDFq3tKp49zjWkPqDk7oZJ");
 
+/// Maximum length of a `Multisig`/`Transaction` name.
+pub const MAX_NAME_LEN: usize = 32;
+/// Maximum number of owners a `Transaction`'s `signers` list is sized for;
+/// `queue_transaction` rejects multisigs with more owners than this, which
+/// gates `create_transaction` and all `propose_token_*` entry points.
+pub const MAX_OWNERS: usize = 10;
+/// Maximum number of instructions in a single proposal's batch.
+pub const MAX_INSTRUCTIONS: usize = 8;
+/// Maximum number of accounts per instruction in the batch.
+pub const MAX_ACCOUNTS_PER_INSTRUCTION: usize = 16;
+/// Maximum instruction data length, in bytes.
+pub const MAX_INSTRUCTION_DATA_LEN: usize = 512;
+
 #[program]
 pub mod multisig {
     use super::*;
@@ -44,10 +59,11 @@ pub mod multisig {
             InvalidThreshold
         );
         require!(!args.owners.is_empty(), InvalidOwnersLen);
+        require!(args.name.len() <= MAX_NAME_LEN, NameTooLong);
         let min_balance = ctx
             .accounts
             .rent
-            .minimum_balance(MultisigOwner::space_required() as usize);
+            .minimum_balance(8 + MultisigOwner::INIT_SPACE);
         for (index, owner_pubkey) in args.owners.iter().enumerate() {
             let multisig_owner = ctx.remaining_accounts.get(index).unwrap();
             let bump: &u8 = args.bumps.get(index).unwrap();
@@ -64,13 +80,15 @@ pub mod multisig {
             )?
         }
 
+        let owners_amount = args.owners.len() as u64;
         let multisig = ctx.accounts.multisig.deref_mut();
         *multisig = Multisig {
             name: args.name,
             threshold: args.threshold,
             nonce: args.nonce,
             owner_set_seqno: 0,
-            owners_amount: args.owners.len() as u64,
+            owners_amount,
+            owners: args.owners,
         };
         Ok(())
     }
@@ -81,29 +99,109 @@ pub mod multisig {
         ctx: Context<CreateTransaction>,
         args: CreateTransactionArgs,
     ) -> Result<()> {
-        require!(
-            ctx.accounts
-                .proposer
-                .key
-                .eq(&ctx.accounts.multisig_owner.owner),
-            InvalidOwner
-        );
+        queue_transaction(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.transaction,
+            ctx.accounts.multisig_owner.owner,
+            args.name,
+            args.instructions,
+        )
+    }
 
-        let owner_hash = hash_pubkey(ctx.accounts.multisig_owner.owner);
-        let signers = vec![owner_hash];
-        let tx = ctx.accounts.transaction.deref_mut();
-        *tx = Transaction {
-            multisig: ctx.accounts.multisig.key(),
-            program_id: args.pid,
-            name: args.name,
-            accounts: args.accs,
-            data: args.data,
-            did_execute: false,
-            owner_set_seqno: ctx.accounts.multisig.owner_set_seqno,
-            signers,
-        };
+    // Proposes a `spl_token::instruction::mint_to` transaction, with the
+    // multisig signer as the mint authority.
+    pub fn propose_token_mint_to(
+        ctx: Context<ProposeTokenInstruction>,
+        args: ProposeTokenMintToArgs,
+    ) -> Result<()> {
+        let ix = token_instruction::mint_to(
+            &spl_token::ID,
+            &args.mint,
+            &args.destination,
+            &ctx.accounts.multisig_signer.key(),
+            &[],
+            args.amount,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidTokenInstruction))?;
+        queue_transaction(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.transaction,
+            ctx.accounts.multisig_owner.owner,
+            args.name,
+            vec![ix.into()],
+        )
+    }
 
-        Ok(())
+    // Proposes a `spl_token::instruction::transfer` transaction, with the
+    // multisig signer as the source account's authority.
+    pub fn propose_token_transfer(
+        ctx: Context<ProposeTokenInstruction>,
+        args: ProposeTokenTransferArgs,
+    ) -> Result<()> {
+        let ix = token_instruction::transfer(
+            &spl_token::ID,
+            &args.source,
+            &args.destination,
+            &ctx.accounts.multisig_signer.key(),
+            &[],
+            args.amount,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidTokenInstruction))?;
+        queue_transaction(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.transaction,
+            ctx.accounts.multisig_owner.owner,
+            args.name,
+            vec![ix.into()],
+        )
+    }
+
+    // Proposes a `spl_token::instruction::burn` transaction, with the
+    // multisig signer as the token account's authority.
+    pub fn propose_token_burn(
+        ctx: Context<ProposeTokenInstruction>,
+        args: ProposeTokenBurnArgs,
+    ) -> Result<()> {
+        let ix = token_instruction::burn(
+            &spl_token::ID,
+            &args.account,
+            &args.mint,
+            &ctx.accounts.multisig_signer.key(),
+            &[],
+            args.amount,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidTokenInstruction))?;
+        queue_transaction(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.transaction,
+            ctx.accounts.multisig_owner.owner,
+            args.name,
+            vec![ix.into()],
+        )
+    }
+
+    // Proposes a `spl_token::instruction::set_authority` transaction, with
+    // the multisig signer as the current authority.
+    pub fn propose_set_authority(
+        ctx: Context<ProposeTokenInstruction>,
+        args: ProposeSetAuthorityArgs,
+    ) -> Result<()> {
+        let ix = token_instruction::set_authority(
+            &spl_token::ID,
+            &args.account,
+            args.new_authority.as_ref(),
+            args.authority_type.into(),
+            &ctx.accounts.multisig_signer.key(),
+            &[],
+        )
+        .map_err(|_| error!(ErrorCode::InvalidTokenInstruction))?;
+        queue_transaction(
+            &ctx.accounts.multisig,
+            &mut ctx.accounts.transaction,
+            ctx.accounts.multisig_owner.owner,
+            args.name,
+            vec![ix.into()],
+        )
     }
 
     // Approves a transaction on behalf of an owner of the multisig.
@@ -116,17 +214,39 @@ pub mod multisig {
             InvalidOwner
         );
 
-        let owner_hash = hash_pubkey(ctx.accounts.multisig_owner.owner);
+        let owner = ctx.accounts.multisig_owner.owner;
 
         require!(
             !ctx.accounts
                 .transaction
                 .signers
                 .iter()
-                .any(|signer| owner_hash.eq(signer)),
+                .any(|signer| owner.eq(signer)),
             AlreadySigned
         );
-        ctx.accounts.transaction.signers.push(owner_hash);
+        ctx.accounts.transaction.signers.push(owner);
+        Ok(())
+    }
+
+    // Revokes a previously given approval, withdrawing the caller's consent
+    // before the transaction is executed.
+    pub fn revoke(ctx: Context<Revoke>) -> Result<()> {
+        require!(
+            ctx.accounts
+                .multisig_owner
+                .multisig
+                .eq(&ctx.accounts.transaction.multisig),
+            InvalidOwner
+        );
+        require!(!ctx.accounts.transaction.did_execute, AlreadyExecuted);
+
+        let owner = ctx.accounts.multisig_owner.owner;
+        let signers = &mut ctx.accounts.transaction.signers;
+        let position = signers
+            .iter()
+            .position(|signer| owner.eq(signer))
+            .ok_or(ErrorCode::NotSigned)?;
+        signers.remove(position);
         Ok(())
     }
 
@@ -138,17 +258,16 @@ pub mod multisig {
     ) -> Result<()> {
         assert_unique_owners(&args.owners)?;
         let multisig = &mut ctx.accounts.multisig;
-        let min_balance = ctx
-            .accounts
-            .rent
-            .minimum_balance(MultisigOwner::space_required() as usize);
+        let min_balance = ctx.accounts.rent.minimum_balance(8 + MultisigOwner::INIT_SPACE);
         let multisig_signer = ctx.accounts.multisig_signer.as_ref();
         let multisig_pubkey = multisig.key();
         for (index, multisig_owner) in ctx.remaining_accounts.iter().enumerate() {
             match <Account<'info, MultisigOwner>>::try_from(multisig_owner) {
-                Ok(multisig_owner) => {
-                    multisig_owner.close(multisig_signer.clone()).unwrap();
+                Ok(multisig_owner_account) => {
+                    let removed_owner = multisig_owner_account.owner;
+                    multisig_owner_account.close(multisig_signer.clone()).unwrap();
                     multisig.owners_amount -= 1;
+                    multisig.owners.retain(|owner| owner != &removed_owner);
                 }
                 Err(_) => {
                     let owner_pubkey = args.owners.get(index).unwrap();
@@ -163,12 +282,19 @@ pub mod multisig {
                         ctx.program_id,
                     )?;
                     multisig.owners_amount += 1;
+                    multisig.owners.push(*owner_pubkey);
                 }
             }
         }
         if !ctx.remaining_accounts.is_empty() {
             multisig.owner_set_seqno += 1;
+            realloc_multisig_for_owners(
+                multisig,
+                ctx.accounts.payer.as_ref(),
+                &ctx.accounts.rent,
+            )?;
         }
+        require!(multisig.owners_amount as usize <= MAX_OWNERS, TooManyOwners);
 
         // Change threshold
         require!(args.threshold > 0, InvalidThreshold);
@@ -190,25 +316,28 @@ pub mod multisig {
             NotEnoughSigners
         );
 
-        // Execute the transaction signed by the multisig.
-        let mut ix: Instruction = (*ctx.accounts.transaction).deref().into();
-        ix.accounts = ix
-            .accounts
-            .iter()
-            .map(|acc| {
-                let mut acc = acc.clone();
-                if &acc.pubkey == ctx.accounts.multisig_signer.key {
-                    acc.is_signer = true;
-                }
-                acc
-            })
-            .collect();
+        // Execute each instruction in order, signed by the multisig. The whole
+        // batch aborts (via `?`) the moment one instruction fails.
         let multisig_key = ctx.accounts.multisig.key();
         let seeds = &[multisig_key.as_ref(), &[ctx.accounts.multisig.nonce]];
         let signer = &[&seeds[..]];
         let accounts = ctx.remaining_accounts;
 
-        invoke_signed(&ix, accounts, signer)?;
+        for tx_ix in ctx.accounts.transaction.instructions.iter() {
+            let mut ix: Instruction = tx_ix.to_instruction(accounts)?;
+            ix.accounts = ix
+                .accounts
+                .iter()
+                .map(|acc| {
+                    let mut acc = acc.clone();
+                    if &acc.pubkey == ctx.accounts.multisig_signer.key {
+                        acc.is_signer = true;
+                    }
+                    acc
+                })
+                .collect();
+            invoke_signed(&ix, accounts, signer)?;
+        }
 
         // Burn the transaction to ensure one time use.
         ctx.accounts.transaction.did_execute = true;
@@ -235,7 +364,7 @@ pub struct CreateMultisig<'info> {
     #[account(
         init,
         payer = owner,
-        space = Multisig::space_required(&args.owners, &args.name)
+        space = Multisig::space_for_owners(args.owners.len())
     )]
     multisig: Account<'info, Multisig>,
     system_program: Program<'info, System>,
@@ -244,9 +373,7 @@ pub struct CreateMultisig<'info> {
 
 #[derive(AnchorDeserialize, AnchorSerialize)]
 pub struct CreateTransactionArgs {
-    pid: Pubkey,
-    accs: Vec<TransactionAccount>,
-    data: Vec<u8>,
+    instructions: Vec<TransactionInstruction>,
     name: String,
 }
 
@@ -256,15 +383,105 @@ pub struct CreateTransaction<'info> {
     multisig: Account<'info, Multisig>,
     #[account(
         init,
-        payer = proposer,
-        space = Transaction::space_required(&args.accs, &args.data, &args.name)
+        payer = owner,
+        space = 8 + Transaction::INIT_SPACE
     )]
     transaction: Account<'info, Transaction>,
 
-    // One of the owners. Checked in the handler.
+    #[account(has_one = multisig, has_one = owner)]
+    multisig_owner: Account<'info, MultisigOwner>,
+
+    // Must own `multisig_owner`. Checked via `has_one` above.
     #[account(mut)]
-    proposer: Signer<'info>,
+    owner: Signer<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct ProposeTokenMintToArgs {
+    name: String,
+    mint: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct ProposeTokenTransferArgs {
+    name: String,
+    source: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct ProposeTokenBurnArgs {
+    name: String,
+    account: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct ProposeSetAuthorityArgs {
+    name: String,
+    account: Pubkey,
+    authority_type: TokenAuthorityType,
+    new_authority: Option<Pubkey>,
+}
+
+/// Mirrors `spl_token::instruction::AuthorityType`, which doesn't derive
+/// Borsh (de)serialization, so it can be used as an Anchor instruction arg.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy)]
+pub enum TokenAuthorityType {
+    MintTokens,
+    FreezeAccount,
+    AccountOwner,
+    CloseAccount,
+}
+
+impl From<TokenAuthorityType> for spl_token::instruction::AuthorityType {
+    fn from(authority_type: TokenAuthorityType) -> Self {
+        match authority_type {
+            TokenAuthorityType::MintTokens => spl_token::instruction::AuthorityType::MintTokens,
+            TokenAuthorityType::FreezeAccount => {
+                spl_token::instruction::AuthorityType::FreezeAccount
+            }
+            TokenAuthorityType::AccountOwner => {
+                spl_token::instruction::AuthorityType::AccountOwner
+            }
+            TokenAuthorityType::CloseAccount => spl_token::instruction::AuthorityType::CloseAccount,
+        }
+    }
+}
+
+/// Shared account context for the typed `propose_token_*` instructions: each
+/// builds a single `spl_token` CPI instruction with `multisig_signer` as
+/// authority and stores it as a normal `Transaction`, so execution still
+/// flows through `execute_transaction`.
+#[derive(Accounts)]
+pub struct ProposeTokenInstruction<'info> {
+    multisig: Account<'info, Multisig>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Transaction::INIT_SPACE
+    )]
+    transaction: Account<'info, Transaction>,
+
+    #[account(has_one = multisig, has_one = owner)]
     multisig_owner: Account<'info, MultisigOwner>,
+
+    // Must own `multisig_owner`. Checked via `has_one` above.
+    #[account(mut)]
+    owner: Signer<'info>,
+
+    /// CHECK: PDA derived from `multisig`; only its pubkey is used as the
+    /// proposed token authority, never read or written here.
+    #[account(
+        seeds = [multisig.key().as_ref()],
+        bump = multisig.nonce,
+    )]
+    multisig_signer: UncheckedAccount<'info>,
     system_program: Program<'info, System>,
 }
 
@@ -274,9 +491,23 @@ pub struct Approve<'info> {
     multisig: Box<Account<'info, Multisig>>,
     #[account(mut, has_one = multisig)]
     transaction: Box<Account<'info, Transaction>>,
+    #[account(has_one = owner)]
     multisig_owner: Account<'info, MultisigOwner>,
 
-    // One of the multisig owners. Checked in the handler.
+    // Must own `multisig_owner`. Checked via `has_one` above.
+    owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Revoke<'info> {
+    #[account(constraint = multisig.owner_set_seqno == transaction.owner_set_seqno)]
+    multisig: Box<Account<'info, Multisig>>,
+    #[account(mut, has_one = multisig)]
+    transaction: Box<Account<'info, Transaction>>,
+    #[account(has_one = owner)]
+    multisig_owner: Account<'info, MultisigOwner>,
+
+    // Must own `multisig_owner`. Checked via `has_one` above.
     owner: Signer<'info>,
 }
 
@@ -290,6 +521,12 @@ pub struct Auth<'info> {
         bump = multisig.nonce,
     )]
     multisig_signer: Signer<'info>,
+
+    // Funds any rent-exempt shortfall when the owner set grows large enough
+    // to require reallocating the multisig account. `multisig_signer` does
+    // not pay for the realloc; it only funds newly created owner accounts.
+    #[account(mut)]
+    payer: Signer<'info>,
     system_program: Program<'info, System>,
     rent: Sysvar<'info, Rent>,
 }
@@ -317,97 +554,217 @@ pub struct UpdateOwnersAndThresholdArgs {
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct Multisig {
+    #[max_len(MAX_NAME_LEN)]
     pub name: String,
     pub threshold: u64,
     pub nonce: u8,
     pub owner_set_seqno: u32,
     pub owners_amount: u64,
+    /// Cache of the current owner set, kept in sync by `create_multisig` and
+    /// `update_owners_and_threshold`. Unlike the other fields this is not
+    /// bounded by a `max_len` - it's reallocated in place as owners are added
+    /// or removed, so `#[max_len(0)]` only reserves the Borsh length prefix.
+    #[max_len(0)]
+    pub owners: Vec<Pubkey>,
 }
 
 impl Multisig {
-    pub fn space_required(owners: &[Pubkey], name: &str) -> usize {
-        8 + std::mem::size_of::<Self>() + owners.len() * std::mem::size_of::<Pubkey>() + name.len()
+    pub fn space_for_owners(owners_amount: usize) -> usize {
+        8 + Multisig::INIT_SPACE + owners_amount * std::mem::size_of::<Pubkey>()
     }
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct Transaction {
     /// The multisig account this transaction belongs to.
     pub multisig: Pubkey,
-    /// Target program to execute against.
-    pub program_id: Pubkey,
     /// Name of transaction.
+    #[max_len(MAX_NAME_LEN)]
     pub name: String,
-    /// Accounts requried for the transaction.
-    pub accounts: Vec<TransactionAccount>,
-    /// Instruction data for the transaction.
-    pub data: Vec<u8>,
+    /// Ordered batch of instructions executed atomically by `execute_transaction`.
+    #[max_len(MAX_INSTRUCTIONS)]
+    pub instructions: Vec<TransactionInstruction>,
     /// Boolean ensuring one time execution.
     pub did_execute: bool,
     /// Owner set sequence number.
     pub owner_set_seqno: u32,
-    /// Signers pubkey hashes
-    pub signers: Vec<u64>,
+    /// Pubkeys of the owners who have approved this transaction.
+    #[max_len(MAX_OWNERS)]
+    pub signers: Vec<Pubkey>,
 }
 
-impl Transaction {
-    pub fn space_required(accounts: &[TransactionAccount], data: &[u8], name: &str) -> usize {
-        8 + std::mem::size_of::<Transaction>()
-            + accounts.len() * std::mem::size_of::<TransactionAccount>()
-            + data.len()
-            + name.len()
+/// A single instruction within a `Transaction`'s batch.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct TransactionInstruction {
+    /// Target program to execute against.
+    pub program_id: Pubkey,
+    /// Accounts required for the instruction.
+    #[max_len(MAX_ACCOUNTS_PER_INSTRUCTION)]
+    pub accounts: Vec<TransactionAccount>,
+    /// Instruction data.
+    #[max_len(MAX_INSTRUCTION_DATA_LEN)]
+    pub data: Vec<u8>,
+}
+
+impl TransactionInstruction {
+    /// Builds the CPI `Instruction`, resolving any `AccountRef::Lookup`
+    /// references against the `AddressLookupTable` accounts passed in
+    /// `remaining_accounts`.
+    pub fn to_instruction(&self, remaining_accounts: &[AccountInfo]) -> Result<Instruction> {
+        Ok(Instruction {
+            program_id: self.program_id,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|acc| acc.to_account_meta(remaining_accounts))
+                .collect::<Result<Vec<_>>>()?,
+            data: self.data.clone(),
+        })
     }
 }
 
-impl From<&Transaction> for Instruction {
-    fn from(tx: &Transaction) -> Instruction {
-        Instruction {
-            program_id: tx.program_id,
-            accounts: tx.accounts.iter().map(Into::into).collect(),
-            data: tx.data.clone(),
+impl From<Instruction> for TransactionInstruction {
+    fn from(ix: Instruction) -> TransactionInstruction {
+        TransactionInstruction {
+            program_id: ix.program_id,
+            accounts: ix.accounts.iter().map(Into::into).collect(),
+            data: ix.data,
         }
     }
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct MultisigOwner {
     pub multisig: Pubkey,
     pub owner: Pubkey,
 }
 
-impl MultisigOwner {
-    pub fn space_required() -> usize {
-        72
-    }
+/// An account reference within a `TransactionInstruction`: either the real
+/// `Pubkey` directly, or a `(lookup_table, index)` pair resolved at execution
+/// time against an on-chain Address Lookup Table, the way versioned
+/// transactions resolve their account keys.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub enum AccountRef {
+    Direct(Pubkey),
+    Lookup { lookup_table: Pubkey, index: u16 },
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
 pub struct TransactionAccount {
-    pub pubkey: Pubkey,
+    pub pubkey: AccountRef,
     pub is_signer: bool,
     pub is_writable: bool,
 }
 
-impl From<&TransactionAccount> for AccountMeta {
-    fn from(account: &TransactionAccount) -> AccountMeta {
-        match account.is_writable {
-            false => AccountMeta::new_readonly(account.pubkey, account.is_signer),
-            true => AccountMeta::new(account.pubkey, account.is_signer),
-        }
+impl TransactionAccount {
+    fn to_account_meta(&self, remaining_accounts: &[AccountInfo]) -> Result<AccountMeta> {
+        let pubkey = match &self.pubkey {
+            AccountRef::Direct(pubkey) => *pubkey,
+            AccountRef::Lookup {
+                lookup_table,
+                index,
+            } => {
+                let lookup_table_account = remaining_accounts
+                    .iter()
+                    .find(|info| info.key == lookup_table)
+                    .ok_or(ErrorCode::LookupTableNotProvided)?;
+                require!(
+                    lookup_table_account.owner == &address_lookup_table::program::ID,
+                    InvalidLookupTableOwner
+                );
+                let data = lookup_table_account.try_borrow_data()?;
+                let table = AddressLookupTable::deserialize(&data)
+                    .map_err(|_| error!(ErrorCode::InvalidLookupTableData))?;
+                *table
+                    .addresses
+                    .get(*index as usize)
+                    .ok_or(ErrorCode::LookupTableIndexOutOfRange)?
+            }
+        };
+        Ok(match self.is_writable {
+            false => AccountMeta::new_readonly(pubkey, self.is_signer),
+            true => AccountMeta::new(pubkey, self.is_signer),
+        })
     }
 }
 
 impl From<&AccountMeta> for TransactionAccount {
     fn from(account_meta: &AccountMeta) -> TransactionAccount {
         TransactionAccount {
-            pubkey: account_meta.pubkey,
+            pubkey: AccountRef::Direct(account_meta.pubkey),
             is_signer: account_meta.is_signer,
             is_writable: account_meta.is_writable,
         }
     }
 }
 
+/// Grows or shrinks the `Multisig` account's data to fit its current owner
+/// cache, funding any additional rent-exempt balance from `payer`.
+fn realloc_multisig_for_owners<'info>(
+    multisig: &Account<'info, Multisig>,
+    payer: &AccountInfo<'info>,
+    rent: &Rent,
+) -> Result<()> {
+    let multisig_ai = multisig.to_account_info();
+    let new_space = Multisig::space_for_owners(multisig.owners.len());
+    if new_space == multisig_ai.data_len() {
+        return Ok(());
+    }
+
+    let new_min_balance = rent.minimum_balance(new_space);
+    let current_balance = multisig_ai.lamports();
+    if new_min_balance > current_balance {
+        let delta = new_min_balance - current_balance;
+        invoke(
+            &system_instruction::transfer(payer.key, multisig_ai.key, delta),
+            &[payer.clone(), multisig_ai.clone()],
+        )?;
+    }
+
+    multisig_ai.realloc(new_space, true)?;
+    Ok(())
+}
+
+/// Validates and stores a proposal's instruction batch, signed automatically
+/// by `proposer_owner`. Shared by `create_transaction` and the typed
+/// `propose_token_*` instructions.
+fn queue_transaction<'info>(
+    multisig: &Account<'info, Multisig>,
+    transaction: &mut Account<'info, Transaction>,
+    proposer_owner: Pubkey,
+    name: String,
+    instructions: Vec<TransactionInstruction>,
+) -> Result<()> {
+    require!(
+        multisig.owners_amount as usize <= MAX_OWNERS,
+        TooManyOwners
+    );
+    require!(name.len() <= MAX_NAME_LEN, NameTooLong);
+    require!(instructions.len() <= MAX_INSTRUCTIONS, TooManyInstructions);
+    for ix in instructions.iter() {
+        require!(
+            ix.accounts.len() <= MAX_ACCOUNTS_PER_INSTRUCTION,
+            TooManyAccounts
+        );
+        require!(ix.data.len() <= MAX_INSTRUCTION_DATA_LEN, InstructionDataTooLarge);
+    }
+
+    let tx = transaction.deref_mut();
+    *tx = Transaction {
+        multisig: multisig.key(),
+        name,
+        instructions,
+        did_execute: false,
+        owner_set_seqno: multisig.owner_set_seqno,
+        signers: vec![proposer_owner],
+    };
+    Ok(())
+}
+
 fn assert_unique_owners(owners: &[Pubkey]) -> Result<()> {
     for (i, owner) in owners.iter().enumerate() {
         require!(
@@ -432,7 +789,7 @@ fn build_multisig_owner<'info>(
             multisig_signer.key,
             multisig_owner.key,
             min_balance,
-            MultisigOwner::space_required() as u64,
+            (8 + MultisigOwner::INIT_SPACE) as u64,
             program_id,
         ),
         &[multisig_signer.clone(), multisig_owner.clone()],
@@ -452,12 +809,6 @@ fn build_multisig_owner<'info>(
     Ok(())
 }
 
-pub fn hash_pubkey(pubkey: Pubkey) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    pubkey.to_bytes().hash(&mut hasher);
-    hasher.finish()
-}
-
 #[error]
 pub enum ErrorCode {
     #[msg("The given owner is not part of this multisig.")]
@@ -480,4 +831,96 @@ pub enum ErrorCode {
     UniqueOwners,
     #[msg("The owner has already signed the transaction")]
     AlreadySigned,
+    #[msg("The owner has not signed this transaction")]
+    NotSigned,
+    #[msg("No remaining account matches the given lookup table")]
+    LookupTableNotProvided,
+    #[msg("The lookup table account is not owned by the address lookup table program")]
+    InvalidLookupTableOwner,
+    #[msg("The lookup table account could not be deserialized")]
+    InvalidLookupTableData,
+    #[msg("The lookup table index is out of range")]
+    LookupTableIndexOutOfRange,
+    #[msg("Name must be no longer than MAX_NAME_LEN bytes")]
+    NameTooLong,
+    #[msg("The multisig has more owners than a transaction can track signers for")]
+    TooManyOwners,
+    #[msg("A transaction may contain at most MAX_INSTRUCTIONS instructions")]
+    TooManyInstructions,
+    #[msg("An instruction may reference at most MAX_ACCOUNTS_PER_INSTRUCTION accounts")]
+    TooManyAccounts,
+    #[msg("Instruction data must be no longer than MAX_INSTRUCTION_DATA_LEN bytes")]
+    InstructionDataTooLarge,
+    #[msg("Failed to build the underlying spl_token instruction")]
+    InvalidTokenInstruction,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_unique_owners_accepts_distinct_pubkeys() {
+        let owners = vec![Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        assert!(assert_unique_owners(&owners).is_ok());
+    }
+
+    #[test]
+    fn assert_unique_owners_rejects_duplicates() {
+        let owner = Pubkey::new_unique();
+        let owners = vec![owner, Pubkey::new_unique(), owner];
+        assert!(assert_unique_owners(&owners).is_err());
+    }
+
+    #[test]
+    fn space_for_owners_grows_by_one_pubkey_per_owner() {
+        let base = Multisig::space_for_owners(1);
+        let grown = Multisig::space_for_owners(2);
+        assert_eq!(grown - base, std::mem::size_of::<Pubkey>());
+    }
+
+    #[test]
+    fn transaction_account_to_account_meta_maps_direct_refs() {
+        let pubkey = Pubkey::new_unique();
+        let account = TransactionAccount {
+            pubkey: AccountRef::Direct(pubkey),
+            is_signer: true,
+            is_writable: false,
+        };
+        let meta = account.to_account_meta(&[]).unwrap();
+        assert_eq!(meta.pubkey, pubkey);
+        assert!(meta.is_signer);
+        assert!(!meta.is_writable);
+    }
+
+    #[test]
+    fn transaction_account_to_account_meta_requires_lookup_table_in_remaining_accounts() {
+        let account = TransactionAccount {
+            pubkey: AccountRef::Lookup {
+                lookup_table: Pubkey::new_unique(),
+                index: 0,
+            },
+            is_signer: false,
+            is_writable: true,
+        };
+        assert!(account.to_account_meta(&[]).is_err());
+    }
+
+    #[test]
+    fn transaction_instruction_round_trips_through_solana_instruction() {
+        let program_id = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let ix = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new(pubkey, true)],
+            data: vec![1, 2, 3],
+        };
+        let tx_ix: TransactionInstruction = ix.clone().into();
+        let rebuilt = tx_ix.to_instruction(&[]).unwrap();
+        assert_eq!(rebuilt.program_id, ix.program_id);
+        assert_eq!(rebuilt.data, ix.data);
+        assert_eq!(rebuilt.accounts.len(), 1);
+        assert_eq!(rebuilt.accounts[0].pubkey, pubkey);
+        assert!(rebuilt.accounts[0].is_signer);
+    }
 }